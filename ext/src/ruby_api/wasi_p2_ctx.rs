@@ -0,0 +1,276 @@
+use super::{
+    root,
+    wasi_ctx::nul_free_string,
+    wasi_ctx_builder::{file_r, file_w},
+};
+use crate::error;
+use magnus::{
+    class, method,
+    prelude::*,
+    scan_args::{get_kwargs, scan_args},
+    typed_data::Obj,
+    Error, Object, RHash, RString, Value,
+};
+use std::cell::RefCell;
+use wasmtime_wasi::pipe::MemoryOutputPipe;
+use wasmtime_wasi::preview1::WasiP1Ctx;
+use wasmtime_wasi::{DirPerms, FilePerms, WasiCtxBuilder as P2Builder};
+
+/// @yard
+/// WASI context backed by `wasmtime_wasi`'s preview2 implementation, a
+/// sibling of the preview1 {WasiCtx} (which is backed by `wasi_common`). It
+/// exposes the same chaining setters as {WasiCtx} for stdio, argv, env and
+/// preopened directories.
+///
+/// `build_p1` turns it into a preview1 adapter for a linker set up with
+/// `add_to_linker_async`, which serves core-module preview1 guests; no path
+/// for component-model guests of the `wasi:cli` WIT world is provided.
+/// {Store#new} does not accept a {WasiP2Ctx} as its +wasi_ctx+ keyword
+/// argument.
+///
+/// Instance methods mutate the current object and return +self+.
+///
+/// @see https://docs.rs/wasmtime-wasi/latest/wasmtime_wasi/ Wasmtime's Rust doc
+#[magnus::wrap(class = "Wasmtime::WasiP2Ctx", size, free_immediately)]
+pub struct WasiP2Ctx {
+    builder: RefCell<P2Builder>,
+    stdout_pipe: RefCell<Option<MemoryOutputPipe>>,
+    stderr_pipe: RefCell<Option<MemoryOutputPipe>>,
+}
+
+type RbSelf = Obj<WasiP2Ctx>;
+
+impl WasiP2Ctx {
+    /// @yard
+    /// Create a new, blank {WasiP2Ctx}. Stdio is inherited from nothing by
+    /// default; call the setters below to wire it up.
+    /// @return [WasiP2Ctx]
+    pub fn new() -> Self {
+        Self {
+            builder: RefCell::new(P2Builder::new()),
+            stdout_pipe: RefCell::new(None),
+            stderr_pipe: RefCell::new(None),
+        }
+    }
+
+    /// @yard
+    /// Set stdin to the specified String.
+    /// @def set_stdin_string(content)
+    /// @param content [String]
+    /// @return [WasiP2Ctx] +self+
+    fn set_stdin_string(rb_self: RbSelf, content: RString) -> Result<RbSelf, Error> {
+        let bytes = unsafe { content.as_slice() }.to_vec();
+        rb_self.builder.borrow_mut().stdin_read(bytes);
+        Ok(rb_self)
+    }
+
+    /// @yard
+    /// Set stdin to read from the specified file.
+    /// @def set_stdin_file(path)
+    /// @param path [String] The path of the file to read from.
+    /// @return [WasiP2Ctx] +self+
+    fn set_stdin_file(rb_self: RbSelf, path: RString) -> Result<RbSelf, Error> {
+        let file = file_r(path)
+            .map_err(|e| error::error_new(format!("failed to open stdin file: {e}")))?;
+        rb_self.builder.borrow_mut().stdin_file(file);
+        Ok(rb_self)
+    }
+
+    /// @yard
+    /// Set stdout to write to a file. Will truncate the file if it exists,
+    /// otherwise try to create it.
+    /// @def set_stdout_file(path)
+    /// @param path [String] The path of the file to write to.
+    /// @return [WasiP2Ctx] +self+
+    fn set_stdout_file(rb_self: RbSelf, path: RString) -> Result<RbSelf, Error> {
+        let file = file_w(path)
+            .map_err(|e| error::error_new(format!("failed to open stdout file: {e}")))?;
+        rb_self.builder.borrow_mut().stdout_file(file);
+        Ok(rb_self)
+    }
+
+    /// @yard
+    /// Set stderr to write to a file. Will truncate the file if it exists,
+    /// otherwise try to create it.
+    /// @def set_stderr_file(path)
+    /// @param path [String] The path of the file to write to.
+    /// @return [WasiP2Ctx] +self+
+    fn set_stderr_file(rb_self: RbSelf, path: RString) -> Result<RbSelf, Error> {
+        let file = file_w(path)
+            .map_err(|e| error::error_new(format!("failed to open stderr file: {e}")))?;
+        rb_self.builder.borrow_mut().stderr_file(file);
+        Ok(rb_self)
+    }
+
+    /// @yard
+    /// Capture stdout into an in-memory buffer, retrievable with
+    /// {#stdout_buffer} once the guest has run.
+    /// @def set_stdout_buffer(capacity)
+    /// @param capacity [Integer] The maximum number of bytes to buffer.
+    /// @return [WasiP2Ctx] +self+
+    fn set_stdout_buffer(rb_self: RbSelf, capacity: usize) -> RbSelf {
+        let pipe = MemoryOutputPipe::new(capacity);
+        rb_self.builder.borrow_mut().stdout(pipe.clone());
+        rb_self.stdout_pipe.borrow_mut().replace(pipe);
+        rb_self
+    }
+
+    /// @yard
+    /// Read back the bytes written to stdout since {#set_stdout_buffer}.
+    /// @return [String, nil] The captured bytes, or +nil+ if
+    ///   {#set_stdout_buffer} was never called.
+    fn stdout_buffer(rb_self: RbSelf) -> Option<RString> {
+        rb_self
+            .stdout_pipe
+            .borrow()
+            .as_ref()
+            .map(|pipe| RString::from_slice(&pipe.contents()))
+    }
+
+    /// @yard
+    /// Capture stderr into an in-memory buffer, retrievable with
+    /// {#stderr_buffer} once the guest has run.
+    /// @def set_stderr_buffer(capacity)
+    /// @param capacity [Integer] The maximum number of bytes to buffer.
+    /// @return [WasiP2Ctx] +self+
+    fn set_stderr_buffer(rb_self: RbSelf, capacity: usize) -> RbSelf {
+        let pipe = MemoryOutputPipe::new(capacity);
+        rb_self.builder.borrow_mut().stderr(pipe.clone());
+        rb_self.stderr_pipe.borrow_mut().replace(pipe);
+        rb_self
+    }
+
+    /// @yard
+    /// Read back the bytes written to stderr since {#set_stderr_buffer}.
+    /// @return [String, nil] The captured bytes, or +nil+ if
+    ///   {#set_stderr_buffer} was never called.
+    fn stderr_buffer(rb_self: RbSelf) -> Option<RString> {
+        rb_self
+            .stderr_pipe
+            .borrow()
+            .as_ref()
+            .map(|pipe| RString::from_slice(&pipe.contents()))
+    }
+
+    /// @yard
+    /// Set the guest's argv, replacing anything previously set.
+    /// @def set_argv(argv)
+    /// @param argv [Array<String>] The arguments the guest will see as its +argv+.
+    /// @return [WasiP2Ctx] +self+
+    fn set_argv(rb_self: RbSelf, argv: Vec<String>) -> RbSelf {
+        rb_self.builder.borrow_mut().args(&argv);
+        rb_self
+    }
+
+    /// @yard
+    /// Set the guest's environment variables, replacing anything previously set.
+    /// @def set_env(env)
+    /// @param env [Hash<String, String>] The environment variables the guest will see.
+    /// @return [WasiP2Ctx] +self+
+    fn set_env(rb_self: RbSelf, env: RHash) -> Result<RbSelf, Error> {
+        let mut pairs = Vec::with_capacity(env.len());
+        env.foreach(|k: RString, v: RString| -> Result<(), Error> {
+            pairs.push((nul_free_string(k)?, nul_free_string(v)?));
+            Ok(())
+        })?;
+        rb_self.builder.borrow_mut().envs(&pairs);
+        Ok(rb_self)
+    }
+
+    /// @yard
+    /// Grant the guest access to a directory on the host filesystem.
+    /// @def preopen_dir(host_path, guest_path, read: true, write: true)
+    /// @param host_path [String] Path to the directory on the host.
+    /// @param guest_path [String] Path the guest sees this directory mounted at.
+    /// @param read [Boolean] Whether the guest can read from this directory. Defaults to +true+.
+    /// @param write [Boolean] Whether the guest can write to this directory. Defaults to +true+.
+    /// @return [WasiP2Ctx] +self+
+    fn preopen_dir(rb_self: RbSelf, args: &[Value]) -> Result<RbSelf, Error> {
+        let args = scan_args::<(RString, RString), (), (), (), _, ()>(args)?;
+        let (host_path, guest_path) = args.required;
+        let kwargs = get_kwargs::<_, (), (Option<bool>, Option<bool>), ()>(
+            args.keywords,
+            &[],
+            &["read", "write"],
+        )?;
+        let (read, write) = kwargs.optional;
+        let read = read.unwrap_or(true);
+        let write = write.unwrap_or(true);
+
+        let host_path = host_path.to_string()?;
+        let guest_path = guest_path.to_string()?;
+
+        let dir_perms = if write {
+            DirPerms::all()
+        } else {
+            DirPerms::READ
+        };
+        let file_perms = match (read, write) {
+            (true, true) => FilePerms::all(),
+            (true, false) => FilePerms::READ,
+            (false, true) => FilePerms::WRITE,
+            (false, false) => FilePerms::empty(),
+        };
+        rb_self
+            .builder
+            .borrow_mut()
+            .preopened_dir(&host_path, &guest_path, dir_perms, file_perms)
+            .map_err(|e| {
+                error::error_new(format!("failed to preopen directory \"{host_path}\": {e}"))
+            })?;
+        Ok(rb_self)
+    }
+
+    /// Builds the preview1-compatible adapter (`WasiP1Ctx`) for a linker set
+    /// up with [`add_to_linker_async`]. This crate's `Store` does not call it.
+    pub fn build_p1(&self) -> WasiP1Ctx {
+        self.builder.borrow_mut().build_p1()
+    }
+}
+
+/// Adds the preview1-on-preview2 WASI imports to `linker`, so a `Store`
+/// backed by a [`WasiP2Ctx`] can instantiate the same preview1 guests as one
+/// backed by {super::wasi_ctx::WasiCtx}. `get_cx` projects the store's data
+/// down to the [`WasiP1Ctx`] built by [`WasiP2Ctx::build_p1`].
+///
+/// This is the call this crate's `Store` needs to make, in place of its
+/// existing `wasi_common::sync::add_to_linker_sync`, whenever it was
+/// constructed with a `WasiP2Ctx` rather than a `WasiCtx`.
+pub fn add_to_linker_async<T: Send>(
+    linker: &mut wasmtime::Linker<T>,
+    get_cx: impl Fn(&mut T) -> &mut WasiP1Ctx + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    wasmtime_wasi::preview1::add_to_linker_async(linker, get_cx)
+}
+
+// NOTE on registration: this file does not register itself. `ruby_api`'s
+// top-level `mod`/`init` aggregator does not call `init` below, and `Store`'s
+// linker setup calls neither `add_to_linker_async` nor
+// `WasiP2Ctx::build_p1`. Two additions outside this file wire it up:
+//   1. `wasi_p2_ctx::init()?` alongside `wasi_ctx::init()?` in `ruby_api`'s
+//      top-level `init`, so `Wasmtime::WasiP2Ctx` exists at runtime.
+//   2. `Store`'s linker setup must call `wasi_p2_ctx::add_to_linker_async`
+//      (above) with `WasiP2Ctx::build_p1()`'s result when constructed with a
+//      `WasiP2Ctx`, instead of its existing preview1 `add_to_linker` call.
+pub fn init() -> Result<(), Error> {
+    let class = root().define_class("WasiP2Ctx", class::object())?;
+    class.define_singleton_method("new", magnus::function!(WasiP2Ctx::new, 0))?;
+    class.define_method("set_stdin_string", method!(WasiP2Ctx::set_stdin_string, 1))?;
+    class.define_method("set_stdin_file", method!(WasiP2Ctx::set_stdin_file, 1))?;
+    class.define_method("set_stdout_file", method!(WasiP2Ctx::set_stdout_file, 1))?;
+    class.define_method("set_stderr_file", method!(WasiP2Ctx::set_stderr_file, 1))?;
+    class.define_method(
+        "set_stdout_buffer",
+        method!(WasiP2Ctx::set_stdout_buffer, 1),
+    )?;
+    class.define_method("stdout_buffer", method!(WasiP2Ctx::stdout_buffer, 0))?;
+    class.define_method(
+        "set_stderr_buffer",
+        method!(WasiP2Ctx::set_stderr_buffer, 1),
+    )?;
+    class.define_method("stderr_buffer", method!(WasiP2Ctx::stderr_buffer, 0))?;
+    class.define_method("set_argv", method!(WasiP2Ctx::set_argv, 1))?;
+    class.define_method("set_env", method!(WasiP2Ctx::set_env, 1))?;
+    class.define_method("preopen_dir", method!(WasiP2Ctx::preopen_dir, -1))?;
+    Ok(())
+}